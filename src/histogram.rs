@@ -0,0 +1,94 @@
+// Histogram and multi-window dataset types used to evaluate the WHAM
+// equations. A `Dataset` bundles the per-window `Histogram`s together with
+// the bin geometry and umbrella bias parameters shared across them.
+
+use statistics;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Histogram {
+	pub num_points: usize,
+	pub bins: Vec<f64>,
+	// decorrelated effective sample size, when known. `None` for a
+	// histogram built directly from pre-binned counts, where no raw time
+	// series was available to estimate a statistical inefficiency from.
+	n_eff: Option<f64>,
+}
+
+impl Histogram {
+	pub fn new(num_points: usize, bins: Vec<f64>) -> Histogram {
+		Histogram { num_points, bins, n_eff: None }
+	}
+
+	// Bin a raw (autocorrelated) reaction-coordinate time series and record
+	// its decorrelated effective sample size alongside the raw counts, so
+	// that windows with long correlation times can be down-weighted in the
+	// WHAM equations instead of over-counting their raw point count.
+	pub fn from_timeseries(series: &[f64], hist_min: f64, hist_max: f64, num_bins: usize) -> Histogram {
+		let width = (hist_max - hist_min) / num_bins as f64;
+		let mut bins = vec![0.0; num_bins];
+		for &x in series {
+			if x >= hist_min && x < hist_max {
+				let bin = (((x - hist_min) / width) as usize).min(num_bins - 1);
+				bins[bin] += 1.0;
+			}
+		}
+		let n_eff = statistics::effective_sample_size(series);
+		Histogram { num_points: series.len(), bins, n_eff: Some(n_eff) }
+	}
+
+	// Effective (decorrelated) number of independent samples in this window,
+	// used by `calc_bin_probability` in place of the raw point count. Falls
+	// back to the raw count when the window has no raw time series to
+	// estimate a statistical inefficiency from.
+	pub fn effective_num_points(&self) -> f64 {
+		self.n_eff.unwrap_or(self.num_points as f64)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dataset {
+	pub num_bins: usize,
+	pub bins_per_dim: Vec<usize>,
+	pub spring_const: Vec<f64>,
+	pub hist_min: Vec<f64>,
+	pub hist_max: Vec<f64>,
+	pub bias_center: Vec<f64>,
+	pub num_points: Vec<f64>,
+	pub kT: f64,
+	pub histograms: Vec<Histogram>,
+	pub cyclic: bool,
+	pub num_windows: usize,
+}
+
+impl Dataset {
+	pub fn new(num_bins: usize, bins_per_dim: Vec<usize>, spring_const: Vec<f64>,
+	           hist_min: Vec<f64>, hist_max: Vec<f64>, bias_center: Vec<f64>,
+	           num_points: Vec<f64>, kT: f64, histograms: Vec<Histogram>, cyclic: bool) -> Dataset {
+		let num_windows = histograms.len();
+		Dataset { num_bins, bins_per_dim, spring_const, hist_min, hist_max, bias_center,
+		          num_points, kT, histograms, cyclic, num_windows }
+	}
+
+	// coordinate of the center of a (currently 1D) bin
+	// TODO generalize to N dimensions once bins_per_dim.len() > 1 is supported
+	pub fn get_coords_for_bin(&self, bin: usize) -> Vec<f64> {
+		let width = (self.hist_max[0] - self.hist_min[0]) / self.bins_per_dim[0] as f64;
+		vec![self.hist_min[0] + (bin as f64 + 0.5) * width]
+	}
+
+	// harmonic umbrella bias weight exp(-U_i(x)/kT) of window `window` at the
+	// coordinate of `bin`
+	pub fn calc_bias(&self, bin: usize, window: usize) -> f64 {
+		let x = self.get_coords_for_bin(bin)[0];
+		let center = self.bias_center[window];
+		let k = self.spring_const[0];
+		(-0.5 * k * (x - center).powi(2) / self.kT).exp()
+	}
+}
+
+impl ::std::fmt::Display for Dataset {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		write!(f, "Dataset: {} windows, {} bins, hist_min={:?}, hist_max={:?}",
+		       self.num_windows, self.num_bins, self.hist_min, self.hist_max)
+	}
+}
@@ -0,0 +1,179 @@
+// Reading the WHAM metadata file into a `histogram::Dataset`, and writing
+// the converged PMF back out, in either of `Config`'s two output formats.
+
+use {Config, OutputFormat};
+use histogram::{Dataset, Histogram};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::result::Result;
+
+// Full structured result of a WHAM run, written as JSON under
+// `OutputFormat::Json` so downstream tooling (plotting, re-analysis,
+// pipeline orchestration) can consume it programmatically, and so a later
+// run can resume from it via `read_data`.
+#[derive(Serialize)]
+struct RunResult<'a> {
+	config: &'a Config,
+	dataset: &'a Dataset,
+	F: &'a [f64],
+	bin_coords: Vec<f64>,
+	P: &'a [f64],
+	free_energy: &'a [f64],
+	free_energy_error: &'a [f64],
+}
+
+// On-disk checkpoint of a run, as written by `write_results` under
+// `OutputFormat::Json`: the full dataset plus the converged bias offsets are
+// all `read_data` needs to resume a partially-converged run, without
+// re-parsing (and re-binning) the original metadata file from scratch.
+#[derive(Deserialize)]
+struct Checkpoint {
+	dataset: Dataset,
+	F: Vec<f64>,
+}
+
+// Each line of the metadata file is:
+//   <path> <bias-center> <spring-const>
+// `<path>` points at either a pre-binned two-column (value, count) histogram
+// file, or, when `cfg.decorrelate` is set, a raw one-column reaction
+// coordinate time series that is binned and decorrelated here.
+//
+// If `cfg.resume_from` is set, the metadata file is not read at all: the
+// dataset and bias offsets are restored from that previous JSON checkpoint
+// instead, and returned as the second element of the tuple in place of the
+// default flat initial guess.
+pub fn read_data(cfg: &Config) -> Result<(Dataset, Option<Vec<f64>>), Box<dyn Error>> {
+    if let Some(path) = &cfg.resume_from {
+        println!("Resuming from checkpoint {}", path);
+        let file = File::open(path)?;
+        let checkpoint: Checkpoint = ::serde_json::from_reader(file)?;
+        return Ok((checkpoint.dataset, Some(checkpoint.F)));
+    }
+
+    let metadata = BufReader::new(File::open(&cfg.metadata_file)?);
+
+    let mut histograms = Vec::new();
+    let mut bias_center = Vec::new();
+    let mut spring_const = Vec::new();
+    let mut num_points = Vec::new();
+
+    for line in metadata.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || fields[0].starts_with('#') {
+            continue;
+        }
+
+        let path = fields[0];
+        let center: f64 = fields[1].parse()?;
+        let k: f64 = fields[2].parse()?;
+
+        let histogram = if cfg.decorrelate {
+            let series = read_timeseries(path)?;
+            Histogram::from_timeseries(&series, cfg.hist_min[0], cfg.hist_max[0], cfg.num_bins[0])
+        } else {
+            read_histogram(path, cfg.hist_min[0], cfg.hist_max[0], cfg.num_bins[0])?
+        };
+
+        bias_center.push(center);
+        // spring_const is shared across windows for this (1D) dataset; keep
+        // the first value encountered.
+        if spring_const.is_empty() {
+            spring_const.push(k);
+        }
+        num_points.push(histogram.num_points as f64);
+        histograms.push(histogram);
+    }
+
+    if histograms.is_empty() {
+        return Err(From::from("No datapoints in histogram boundaries."));
+    }
+
+    let dataset = Dataset::new(cfg.num_bins[0], cfg.num_bins.clone(), spring_const,
+                                cfg.hist_min.clone(), cfg.hist_max.clone(), bias_center,
+                                num_points, cfg.temperature, histograms, cfg.cyclic);
+    Ok((dataset, None))
+}
+
+// read a raw, one-sample-per-line reaction coordinate time series
+fn read_timeseries(path: &str) -> Result<Vec<f64>, Box<dyn Error>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut series = Vec::new();
+    for line in file.lines() {
+        let line = line?;
+        if let Some(value) = line.split_whitespace().last() {
+            series.push(value.parse()?);
+        }
+    }
+    Ok(series)
+}
+
+// read a pre-binned two-column (value, count) histogram file and fold it
+// into `num_bins` equal-width bins between hist_min and hist_max
+fn read_histogram(path: &str, hist_min: f64, hist_max: f64, num_bins: usize) -> Result<Histogram, Box<dyn Error>> {
+    let file = BufReader::new(File::open(path)?);
+    let width = (hist_max - hist_min) / num_bins as f64;
+    let mut bins = vec![0.0; num_bins];
+    let mut total = 0usize;
+
+    for line in file.lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            continue;
+        }
+        let value: f64 = fields[0].parse()?;
+        let count: f64 = fields[1].parse()?;
+        if value >= hist_min && value < hist_max {
+            let bin = (((value - hist_min) / width) as usize).min(num_bins - 1);
+            bins[bin] += count;
+            total += count as usize;
+        }
+    }
+
+    Ok(Histogram::new(total, bins))
+}
+
+// Write the converged PMF to `cfg.output`, in whichever of `cfg.format`'s
+// two shapes was requested: a single structured JSON document (which also
+// doubles as a checkpoint `read_data` can resume from), or the plain
+// tab-separated text this tool has always produced.
+pub fn write_results(cfg: &Config, ds: &Dataset, F: &[f64], free_energy: &[f64], free_energy_error: &[f64],
+                      P: &[f64], bootstrap_std: Option<&[f64]>) -> Result<(), Box<dyn Error>> {
+    match cfg.format {
+        OutputFormat::Json => {
+            let result = RunResult {
+                config: cfg,
+                dataset: ds,
+                F,
+                bin_coords: (0..ds.num_bins).map(|bin| ds.get_coords_for_bin(bin)[0]).collect(),
+                P,
+                free_energy,
+                free_energy_error,
+            };
+            let file = File::create(&cfg.output)?;
+            ::serde_json::to_writer_pretty(file, &result)?;
+        }
+        OutputFormat::Text => {
+            let mut out = File::create(&cfg.output)?;
+
+            match bootstrap_std {
+                Some(_) => writeln!(out, "#x\t\tFree Energy\t\tsigma(A)\t\tstd(Free Energy)\t\tP(x)")?,
+                None => writeln!(out, "#x\t\tFree Energy\t\tsigma(A)\t\tP(x)")?,
+            }
+
+            for bin in 0..ds.num_bins {
+                let x = ds.get_coords_for_bin(bin)[0];
+                match bootstrap_std {
+                    Some(std) => writeln!(out, "{:9.5}\t{:9.5}\t{:9.5}\t{:9.5}\t{:9.5}",
+                                           x, free_energy[bin], free_energy_error[bin], std[bin], P[bin])?,
+                    None => writeln!(out, "{:9.5}\t{:9.5}\t{:9.5}\t{:9.5}",
+                                      x, free_energy[bin], free_energy_error[bin], P[bin])?,
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -3,28 +3,30 @@ extern crate wham;
 extern crate clap;
 
 use clap::App;
-use wham::Config;
+use wham::{Config, OutputFormat};
 use std::error::Error;
 use std::result::Result;
 use std::process;
 use std::env;
 
 // Parse command line arguments into a Config struct
-fn cli() -> Result<Config, Box<Error>> {
+fn cli() -> Result<Config, Box<dyn Error>> {
 	let yaml = load_yaml!("cli.yml");
 	let matches = App::from_yaml(yaml).get_matches();
 	let metadata_file = matches.value_of("metadata").unwrap().to_string();
-	let hist_min: f32 = matches.value_of("min_hist").unwrap().parse()?;
-	let hist_max: f32 = matches.value_of("max_hist").unwrap().parse()?;
+	let hist_min: f64 = matches.value_of("min_hist").unwrap().parse()?;
+	let hist_max: f64 = matches.value_of("max_hist").unwrap().parse()?;
 	let num_bins: usize = matches.value_of("bins").unwrap().parse()?;
 	let verbose: bool = matches.is_present("verbose");
-	let temperature: f32 = matches.value_of("temperature").unwrap().parse()?;
+	let temperature: f64 = matches.value_of("temperature").unwrap().parse()?;
+	let cyclic: bool = matches.is_present("cyclic");
+	let output: String = matches.value_of("output").unwrap_or("pmf.dat").to_string();
 
-	let tolerance: f32;
+	let tolerance: f64;
 	if matches.is_present("tolerance") {
 		tolerance = matches.value_of("tolerance").unwrap().parse()?;
 	} else {
-		tolerance = std::f32::MIN_POSITIVE;
+		tolerance = std::f64::MIN_POSITIVE;
 	}
 	let max_iterations: usize;
 	if matches.is_present("iterations") {
@@ -32,8 +34,27 @@ fn cli() -> Result<Config, Box<Error>> {
 	} else {
 		max_iterations = std::usize::MAX;
 	}
-	Ok(wham::Config{metadata_file, hist_min, hist_max, num_bins,
-		verbose, tolerance, max_iterations, temperature})
+
+	let bootstrap: Option<usize> = match matches.value_of("bootstrap") {
+		Some(n) => Some(n.parse()?),
+		None => None,
+	};
+	let seed: u64 = match matches.value_of("seed") {
+		Some(s) => s.parse()?,
+		None => 0,
+	};
+	let accelerate: bool = matches.is_present("accelerate");
+
+	let format = match matches.value_of("format") {
+		Some("json") => OutputFormat::Json,
+		_ => OutputFormat::Text,
+	};
+	let resume_from: Option<String> = matches.value_of("resume").map(|s| s.to_string());
+	let decorrelate: bool = matches.is_present("decorrelate");
+
+	Ok(wham::Config{metadata_file, hist_min: vec![hist_min], hist_max: vec![hist_max],
+		num_bins: vec![num_bins], dimens: 1, verbose, tolerance, max_iterations, temperature,
+		cyclic, output, bootstrap, seed, accelerate, format, resume_from, decorrelate})
 }
 
 fn main() {
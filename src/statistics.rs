@@ -38,14 +38,56 @@ pub fn corr(values1: &[f64], values2: &[f64]) -> f64 {
     cov / (std1 * std2)
 }
 
+// normalized autocorrelation of a time series at a given lag:
+// C(t) = cov(x_i, x_{i+t}) / var(x)
+pub fn autocorr(values: &[f64], lag: usize) -> f64 {
+    let n = values.len();
+    let var = var(values);
+    let sum = (0..n - lag)
+        .map(|i| (values[i] - mean(values)) * (values[i + lag] - mean(values)))
+        .sum::<f64>() / (n as f64 - 1.0);
+    sum / var
+}
+
+// statistical inefficiency g of an autocorrelated time series, following the
+// standard estimator used to decorrelate umbrella-sampling windows: integrate
+// the normalized autocorrelation function C(t) until it first drops to zero
+// (or below), then g = 1 + 2*sum_t (1 - t/N) * C(t). An independent,
+// uncorrelated series has g = 1.
+pub fn statistical_inefficiency(values: &[f64]) -> f64 {
+    let n = values.len();
+    let mut g = 1.0;
+    for t in 1..n {
+        let c = autocorr(values, t);
+        if c <= 0.0 {
+            break;
+        }
+        g += 2.0 * (1.0 - t as f64 / n as f64) * c;
+    }
+    g
+}
+
+// effective number of independent samples in an autocorrelated series, used
+// to down-weight umbrella windows whose raw sample count overstates how much
+// independent information they actually carry.
+pub fn effective_sample_size(values: &[f64]) -> f64 {
+    values.len() as f64 / statistical_inefficiency(values)
+}
+
 #[cfg(test)]
 mod tests {
-    
+
+    macro_rules! assert_delta {
+        ($x:expr, $y:expr) => {
+            assert!(($x-$y).abs() < 0.0000001, "{} != {}", $x, $y)
+        }
+    }
+
     #[test]
     fn mean() {
         let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
         let mean = super::mean(&x[..]);
-        assert_approx_eq!(mean, 3.0);
+        assert_delta!(mean, 3.0);
     }
 
     #[test]
@@ -53,15 +95,49 @@ mod tests {
         let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
         let std = super::std(&x[..]);
         let expected = (2.5 as f64).sqrt();
-        assert_approx_eq!(std, expected);
+        assert_delta!(std, expected);
     }
 
     #[test]
     fn var() {
         let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
         let var = super::var(&x[..]);
-        assert_approx_eq!(var, 2.5);
-    }    
+        assert_delta!(var, 2.5);
+    }
+
+    #[test]
+    fn autocorr() {
+        let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+        // the lag-1 covariance of a linear series is biased towards zero by
+        // the truncated (n-lag) overlap, so it normalizes to less than 1.0
+        // even though the series is perfectly linear
+        let c = super::autocorr(&x[..], 1);
+        assert_delta!(c, 0.4);
+    }
+
+    #[test]
+    fn statistical_inefficiency() {
+        let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+        let g = super::statistical_inefficiency(&x[..]);
+        assert!(g >= 1.0);
+    }
+
+    #[test]
+    fn statistical_inefficiency_breaks_at_first_non_positive_lag() {
+        // an alternating series anti-correlates at lag 1 (C(1) < 0), so the
+        // sum must truncate there instead of continuing to accumulate terms.
+        // With no positive-lag term ever added, g reduces to its baseline 1.0.
+        let x = vec![1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        let g = super::statistical_inefficiency(&x[..]);
+        assert_delta!(g, 1.0);
+    }
+
+    #[test]
+    fn effective_sample_size() {
+        let x = vec![1.0, 2.0, 3.0, 4.0, 5.0];
+        let n_eff = super::effective_sample_size(&x[..]);
+        assert!(n_eff <= x.len() as f64);
+    }
 
     #[test]
     fn corr() {
@@ -69,9 +145,9 @@ mod tests {
         let y = vec![3.0, 2.0, 1.0, 0.0, -1.0];
 
         let corr = super::corr(&x[..], &x[..]);
-        assert_approx_eq!(corr, 1.0);
+        assert_delta!(corr, 1.0);
 
         let corr = super::corr(&x[..], &y[..]);
-        assert_approx_eq!(corr, -1.0);
+        assert_delta!(corr, -1.0);
     }
 }